@@ -1,65 +1,505 @@
 use libc::{tcgetattr, tcsetattr, ECHO, ICANON, TCSANOW};
+use std::collections::VecDeque;
 use std::io::{self, Read, Write};
 use std::mem;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
 
 #[non_exhaustive]
 struct Key {}
 
 impl Key {
-    pub const BACKSPACE: u8 = b'\x7F'; // ASCII 127
-    pub const ENTER: u8 = b'\n'; // ASCII 10
+    pub const CTRL_A: u8 = 1; // ASCII 1, emacs-style line start
     pub const CTRL_D: u8 = 4; // ASCII 4
+    pub const CTRL_E: u8 = 5; // ASCII 5, emacs-style line end
+    pub const CTRL_F: u8 = 6; // ASCII 6
+    pub const CTRL_K: u8 = 11; // ASCII 11, kill to end of line
+    pub const ENTER: u8 = b'\r'; // ASCII 13; raw mode clears ICRNL, so Enter arrives as CR
+    pub const CTRL_S: u8 = 19; // ASCII 19
+    pub const CTRL_U: u8 = 21; // ASCII 21, kill to start of line
     pub const ESCAPE: u8 = 27; // ESC key (ASCII 27)
-    pub const ARROW_UP: (u8, u8) = (b'[', b'A'); // Arrow Up (ESC [ A)
-    pub const ARROW_DOWN: (u8, u8) = (b'[', b'B'); // Arrow Down (ESC [ B)
-    pub const ARROW_RIGHT: (u8, u8) = (b'[', b'C'); // Arrow Right (ESC [ C)
-    pub const ARROW_LEFT: (u8, u8) = (b'[', b'D'); // Arrow Left (ESC [ D)
+    pub const BACKSPACE: u8 = b'\x7F'; // ASCII 127
 }
 
-fn set_raw_mode(fd: RawFd) -> io::Result<()> {
-    let mut termios = unsafe {
-        let mut termios = mem::zeroed();
-        if tcgetattr(fd, &mut termios) != 0 {
-            return Err(io::Error::last_os_error());
+/// A fully decoded input event, independent of how many raw bytes it took to
+/// recognize. `main`'s loop dispatches on this instead of matching bytes
+/// directly, so adding a new binding only means extending `KeyDecoder::feed`.
+#[derive(Debug, Clone, Copy)]
+enum Cmd {
+    Insert(char),
+    Newline,
+    Backspace,
+    Delete,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    KillToEol,
+    KillToStart,
+    Save,
+    Search,
+    Quit,
+    /// Terminal entered bracketed-paste mode (`ESC [ 200 ~`).
+    PasteStart,
+    /// Terminal left bracketed-paste mode (`ESC [ 201 ~`).
+    PasteEnd,
+    /// One codepoint of pasted content, to be inserted verbatim rather than
+    /// run through the interactive key handlers.
+    PasteChar(char),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum EscState {
+    None,
+    Esc,
+    Bracket,
+    BracketDigits,
+}
+
+/// Outcome of feeding one byte into the escape-sequence state machine.
+enum RawSeq {
+    Waiting,
+    Invalid,
+    Letter(u8),
+    Code(u32),
+}
+
+/// Decodes raw bytes into `Cmd`s, handling the short arrow-key escapes
+/// (`ESC [ A`..`D`), the longer `ESC [ n ~` forms (Delete/PageUp/PageDown/
+/// bracketed-paste markers), and `ESC [ H`/`ESC [ F` for Home/End. Bytes
+/// outside an escape sequence that aren't bound control bytes go to the
+/// UTF-8 decoder; an unrecognized escape sequence is dropped.
+struct KeyDecoder {
+    esc_state: EscState,
+    digits: Vec<u8>,
+    utf8: Utf8Decoder,
+    in_paste: bool,
+}
+
+impl KeyDecoder {
+    fn new() -> Self {
+        KeyDecoder {
+            esc_state: EscState::None,
+            digits: Vec::new(),
+            utf8: Utf8Decoder::new(),
+            in_paste: false,
+        }
+    }
+
+    fn feed(&mut self, byte: u8) -> Option<Cmd> {
+        if self.esc_state != EscState::None || byte == Key::ESCAPE {
+            return match self.feed_escape(byte) {
+                RawSeq::Waiting | RawSeq::Invalid => None,
+                RawSeq::Letter(b'A') => self.outside_paste(Cmd::MoveUp),
+                RawSeq::Letter(b'B') => self.outside_paste(Cmd::MoveDown),
+                RawSeq::Letter(b'C') => self.outside_paste(Cmd::MoveRight),
+                RawSeq::Letter(b'D') => self.outside_paste(Cmd::MoveLeft),
+                RawSeq::Letter(b'H') => self.outside_paste(Cmd::Home),
+                RawSeq::Letter(b'F') => self.outside_paste(Cmd::End),
+                RawSeq::Letter(_) => None,
+                RawSeq::Code(3) => self.outside_paste(Cmd::Delete),
+                RawSeq::Code(5) => self.outside_paste(Cmd::PageUp),
+                RawSeq::Code(6) => self.outside_paste(Cmd::PageDown),
+                RawSeq::Code(200) => {
+                    self.in_paste = true;
+                    Some(Cmd::PasteStart)
+                }
+                RawSeq::Code(201) => {
+                    self.in_paste = false;
+                    Some(Cmd::PasteEnd)
+                }
+                RawSeq::Code(_) => None,
+            };
+        }
+
+        if self.in_paste {
+            return match byte {
+                b'\n' => Some(Cmd::PasteChar('\n')),
+                c => self.utf8.feed(c).map(Cmd::PasteChar),
+            };
         }
-        termios
-    };
 
-    termios.c_lflag &= !(ICANON | ECHO); // Disable canonical mode and echo
-    termios.c_cc[libc::VMIN] = 1; // Minimum number of characters for read()
-    termios.c_cc[libc::VTIME] = 0; // No timeout
+        match byte {
+            Key::CTRL_A => Some(Cmd::Home),
+            Key::CTRL_E => Some(Cmd::End),
+            Key::CTRL_K => Some(Cmd::KillToEol),
+            Key::CTRL_U => Some(Cmd::KillToStart),
+            Key::CTRL_D => Some(Cmd::Quit),
+            Key::CTRL_S => Some(Cmd::Save),
+            Key::CTRL_F => Some(Cmd::Search),
+            Key::ENTER => Some(Cmd::Newline),
+            Key::BACKSPACE => Some(Cmd::Backspace),
+            c => self.utf8.feed(c).map(Cmd::Insert),
+        }
+    }
 
-    if unsafe { tcsetattr(fd, TCSANOW, &termios) != 0 } {
-        return Err(io::Error::last_os_error());
+    /// A real key sequence landing inside an active paste (rather than the
+    /// `ESC [ 201 ~` terminator) is almost certainly part of the pasted
+    /// content re-parsed as an escape by mistake; swallow it instead of
+    /// running it as a command.
+    fn outside_paste(&self, cmd: Cmd) -> Option<Cmd> {
+        if self.in_paste { None } else { Some(cmd) }
     }
 
-    Ok(())
+    fn feed_escape(&mut self, byte: u8) -> RawSeq {
+        match self.esc_state {
+            EscState::None => {
+                self.esc_state = EscState::Esc;
+                RawSeq::Waiting
+            }
+            EscState::Esc => {
+                if byte == b'[' {
+                    self.esc_state = EscState::Bracket;
+                    RawSeq::Waiting
+                } else {
+                    self.reset_escape();
+                    RawSeq::Invalid
+                }
+            }
+            EscState::Bracket => match byte {
+                b'A' | b'B' | b'C' | b'D' | b'H' | b'F' => {
+                    self.reset_escape();
+                    RawSeq::Letter(byte)
+                }
+                b'0'..=b'9' => {
+                    self.digits.push(byte);
+                    self.esc_state = EscState::BracketDigits;
+                    RawSeq::Waiting
+                }
+                _ => {
+                    self.reset_escape();
+                    RawSeq::Invalid
+                }
+            },
+            EscState::BracketDigits => {
+                if byte.is_ascii_digit() {
+                    self.digits.push(byte);
+                    RawSeq::Waiting
+                } else if byte == b'~' {
+                    let code = std::str::from_utf8(&self.digits)
+                        .ok()
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or(0);
+                    self.reset_escape();
+                    RawSeq::Code(code)
+                } else {
+                    self.reset_escape();
+                    RawSeq::Invalid
+                }
+            }
+        }
+    }
+
+    fn reset_escape(&mut self) {
+        self.esc_state = EscState::None;
+        self.digits.clear();
+    }
 }
 
-fn restore_mode(fd: RawFd) -> io::Result<()> {
-    let mut termios = unsafe {
-        let mut termios = mem::zeroed();
-        if tcgetattr(fd, &mut termios) != 0 {
-            return Err(io::Error::last_os_error());
+/// Ring buffer of previously committed lines, plus a navigation cursor so
+/// ARROW_UP/ARROW_DOWN can walk through it like a shell history.
+struct History {
+    entries: VecDeque<Vec<u8>>,
+    max_len: usize,
+    // Index into `entries` (0 = oldest) of the entry currently shown, or
+    // `None` when the user is back on their own in-progress line.
+    cursor: Option<usize>,
+    // The line the user was typing before they started walking history, so
+    // ARROW_DOWN past the newest entry can restore it.
+    draft: Vec<u8>,
+}
+
+impl History {
+    fn new(max_len: usize) -> Self {
+        History {
+            entries: VecDeque::new(),
+            max_len,
+            cursor: None,
+            draft: Vec::new(),
+        }
+    }
+
+    /// Record a committed line, skipping consecutive duplicates.
+    fn push(&mut self, line: Vec<u8>) {
+        if line.is_empty() {
+            return;
+        }
+        if self.entries.back() != Some(&line) {
+            self.entries.push_back(line);
+            if self.entries.len() > self.max_len {
+                self.entries.pop_front();
+            }
+        }
+        self.cursor = None;
+    }
+
+    /// Step backward (older). `current` is the line the cursor sits on right
+    /// now, stashed as the draft on the first step.
+    fn prev(&mut self, current: &[u8]) -> Option<Vec<u8>> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let next_cursor = match self.cursor {
+            None => {
+                self.draft = current.to_vec();
+                self.entries.len() - 1
+            }
+            Some(0) => 0,
+            Some(i) => i - 1,
+        };
+        self.cursor = Some(next_cursor);
+        self.entries.get(next_cursor).cloned()
+    }
+
+    /// Step forward (newer). Returns the draft once the user walks past the
+    /// newest entry.
+    fn next(&mut self) -> Option<Vec<u8>> {
+        match self.cursor {
+            None => None,
+            Some(i) if i + 1 >= self.entries.len() => {
+                self.cursor = None;
+                Some(mem::take(&mut self.draft))
+            }
+            Some(i) => {
+                self.cursor = Some(i + 1);
+                self.entries.get(i + 1).cloned()
+            }
+        }
+    }
+
+    /// Load history from a dotfile, one entry per line. Missing files yield
+    /// an empty history rather than an error.
+    fn load(path: &Path, max_len: usize) -> io::Result<Self> {
+        let mut history = History::new(max_len);
+        let contents = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(history),
+            Err(e) => return Err(e),
+        };
+        for line in contents.split(|&b| b == b'\n') {
+            if !line.is_empty() {
+                history.push(line.to_vec());
+            }
+        }
+        history.cursor = None;
+        Ok(history)
+    }
+
+    /// Persist history to a dotfile, one entry per line.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = Vec::new();
+        for entry in &self.entries {
+            out.extend_from_slice(entry);
+            out.push(b'\n');
+        }
+        std::fs::write(path, out)
+    }
+}
+
+fn history_path() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home).join(".hasma_repl_history")
+}
+
+/// Incremental UTF-8 decoder. Feed it raw input bytes one at a time; it
+/// returns a `char` once a full sequence has been accumulated, and silently
+/// resets on an invalid lead or continuation byte so one bad byte can't wedge
+/// the decoder forever.
+struct Utf8Decoder {
+    pending: Vec<u8>,
+    remaining: usize,
+}
+
+impl Utf8Decoder {
+    fn new() -> Self {
+        Utf8Decoder {
+            pending: Vec::new(),
+            remaining: 0,
+        }
+    }
+
+    fn feed(&mut self, byte: u8) -> Option<char> {
+        if self.remaining == 0 {
+            if byte < 0x80 {
+                return Some(byte as char);
+            } else if byte & 0xE0 == 0xC0 {
+                self.pending = vec![byte];
+                self.remaining = 1;
+            } else if byte & 0xF0 == 0xE0 {
+                self.pending = vec![byte];
+                self.remaining = 2;
+            } else if byte & 0xF8 == 0xF0 {
+                self.pending = vec![byte];
+                self.remaining = 3;
+            }
+            // Any other lead byte (0x80..=0xBF, 0xF8..=0xFF) is invalid; drop it.
+            None
+        } else if byte & 0xC0 == 0x80 {
+            self.pending.push(byte);
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                let decoded = std::str::from_utf8(&self.pending)
+                    .ok()
+                    .and_then(|s| s.chars().next());
+                self.pending.clear();
+                decoded
+            } else {
+                None
+            }
+        } else {
+            // Expected a continuation byte and didn't get one; abandon the
+            // sequence and let the caller re-feed `byte` as a fresh lead byte
+            // on the next call would be nicer, but in practice a malformed
+            // stream is rare enough that dropping it is sufficient here.
+            self.pending.clear();
+            self.remaining = 0;
+            None
         }
-        termios
+    }
+}
+
+/// Display width of a character in terminal columns. East-Asian wide and
+/// fullwidth characters take two columns; everything else takes one.
+fn char_width(c: char) -> usize {
+    let cp = c as u32;
+    let wide = matches!(cp,
+        0x1100..=0x115F   // Hangul Jamo
+        | 0x2E80..=0x303E  // CJK Radicals, Kangxi, CJK Symbols and Punctuation
+        | 0x3041..=0x33FF  // Hiragana .. CJK Compatibility
+        | 0x3400..=0x4DBF  // CJK Unified Ideographs Extension A
+        | 0x4E00..=0x9FFF  // CJK Unified Ideographs
+        | 0xA000..=0xA4CF  // Yi Syllables
+        | 0xAC00..=0xD7A3  // Hangul Syllables
+        | 0xF900..=0xFAFF  // CJK Compatibility Ideographs
+        | 0xFF00..=0xFF60  // Fullwidth Forms
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1FAFF // Emoji and symbol blocks
+        | 0x20000..=0x3FFFD // CJK Unified Ideographs Extension B..
+    );
+    if wide {
+        2
+    } else {
+        1
+    }
+}
+
+fn find_subslice(haystack: &[char], needle: &[char]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn rfind_subslice(haystack: &[char], needle: &[char]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+static WINCH_RECEIVED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+extern "C" fn handle_sigwinch(_signum: libc::c_int) {
+    WINCH_RECEIVED.store(true, std::sync::atomic::Ordering::SeqCst);
+}
+
+fn install_sigwinch_handler() {
+    unsafe {
+        libc::signal(libc::SIGWINCH, handle_sigwinch as *const () as libc::sighandler_t);
+    }
+}
+
+/// Whether `fd` has a byte ready to read within `timeout_ms`. Raw mode sets
+/// `VMIN=1, VTIME=0`, so a plain blocking read has no timeout of its own;
+/// this lets callers peek for "more bytes coming" (the rest of an escape
+/// sequence) without stalling on a lone key press that isn't one.
+fn input_ready_within(fd: RawFd, timeout_ms: i32) -> bool {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
     };
+    unsafe { libc::poll(&mut pfd, 1, timeout_ms) > 0 }
+}
+
+/// Query the terminal size via `TIOCGWINSZ`, falling back to 80x24 if the fd
+/// isn't a terminal or the ioctl fails.
+fn window_size(fd: RawFd) -> (usize, usize) {
+    let mut ws: libc::winsize = unsafe { mem::zeroed() };
+    let ok = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut ws) } == 0 && ws.ws_row > 0;
+    if ok {
+        (ws.ws_row as usize, ws.ws_col as usize)
+    } else {
+        (24, 80)
+    }
+}
+
+/// Puts `fd` into raw mode for as long as it's alive, restoring whatever
+/// termios settings were originally in place when dropped — including on
+/// panic or early return, since `Drop` runs during unwinding too.
+struct RawModeGuard {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawModeGuard {
+    fn enable(fd: RawFd) -> io::Result<Self> {
+        let original = unsafe {
+            let mut termios = mem::zeroed();
+            if tcgetattr(fd, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios
+        };
+
+        let mut raw = original;
+        // cfmakeraw-equivalent: disable input translation/flow control, output
+        // post-processing, canonical mode, echo, signal generation and
+        // extended input processing, and set 8-bit chars.
+        raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+        raw.c_oflag &= !libc::OPOST;
+        raw.c_cflag |= libc::CS8;
+        raw.c_lflag &= !(ECHO | ICANON | libc::IEXTEN | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1; // Minimum number of characters for read()
+        raw.c_cc[libc::VTIME] = 0; // No timeout
 
-    termios.c_lflag |= ICANON | ECHO; // Restore canonical mode and echo
+        if unsafe { tcsetattr(fd, TCSANOW, &raw) != 0 } {
+            return Err(io::Error::last_os_error());
+        }
 
-    if unsafe { tcsetattr(fd, TCSANOW, &termios) != 0 } {
-        return Err(io::Error::last_os_error());
+        Ok(RawModeGuard { fd, original })
     }
+}
 
-    Ok(())
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        unsafe {
+            tcsetattr(self.fd, TCSANOW, &self.original);
+        }
+    }
 }
 
 struct Canvas<'a> {
     handle: io::StdoutLock<'a>,
-    buffer: Vec<Vec<u8>>,
+    buffer: Vec<Vec<char>>,
     x: usize,
     y: usize,
+    history: History,
+    screen_rows: usize,
+    screen_cols: usize,
+    row_offset: usize,
+    col_offset: usize,
+    filename: Option<String>,
+    dirty: usize,
+    message: String,
+    /// The span `render` should draw in inverse video: (buffer row, start
+    /// char index, length in chars). Set by `incremental_search` while a
+    /// match is live, cleared once the search ends.
+    highlight: Option<(usize, usize, usize)>,
 }
 impl<'a> Write for Canvas<'a> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
@@ -81,27 +521,239 @@ impl<'a> Write for Canvas<'a> {
         self.handle.flush()
     }
 }
+const HISTORY_MAX_LEN: usize = 1000;
+// How many times in a row the quit key must be pressed to discard unsaved
+// changes, mirroring kilo's `KILO_QUIT_TIMES`.
+const QUIT_TIMES: usize = 3;
+
 impl<'a> Canvas<'a> {
-    fn new() -> Self {
+    fn new(history: History, screen_rows: usize, screen_cols: usize) -> Self {
         let stdout = io::stdout();
         Canvas {
             handle: stdout.lock(),
             x: 0,
             y: 0,
             buffer: vec![vec![]],
+            history,
+            screen_rows,
+            screen_cols,
+            row_offset: 0,
+            col_offset: 0,
+            filename: None,
+            dirty: 0,
+            message: String::new(),
+            highlight: None,
         }
     }
     fn clear_view(&mut self) -> io::Result<()> {
         self.write_all(b"\x1B[2J\x1B[H")
     }
+
+    /// Number of rows available for buffer text; the bottom two rows are
+    /// reserved for the status bar and the transient message line.
+    fn text_rows(&self) -> usize {
+        self.screen_rows.saturating_sub(2).max(1)
+    }
+
+    /// Build the inverse-video status line: filename, line count and dirty
+    /// marker on the left, cursor position on the right. Measured and
+    /// truncated in display columns (via `char_width`), not bytes, so a
+    /// multibyte filename can't land the cut mid-codepoint or misalign the
+    /// bar against wide characters.
+    fn status_line(&self) -> String {
+        let name = self.filename.as_deref().unwrap_or("[No Name]");
+        let dirty_marker = if self.dirty > 0 { " (modified)" } else { "" };
+        let left = format!("{} - {} lines{}", name, self.buffer.len(), dirty_marker);
+        let right = format!("{}:{}", self.y + 1, self.x + 1);
+        let width = self.screen_cols;
+
+        let left_width: usize = left.chars().map(char_width).sum();
+        let right_width: usize = right.chars().map(char_width).sum();
+        let mut chars: Vec<char> = left.chars().collect();
+        if left_width + right_width < width {
+            chars.extend(std::iter::repeat_n(' ', width - left_width - right_width));
+            chars.extend(right.chars());
+        }
+
+        let mut out = String::new();
+        let mut out_width = 0;
+        for c in chars {
+            let w = char_width(c);
+            if out_width + w > width {
+                break;
+            }
+            out.push(c);
+            out_width += w;
+        }
+        if out_width < width {
+            out.push_str(&" ".repeat(width - out_width));
+        }
+        out
+    }
+
+    /// Move `row_offset`/`col_offset` just enough to keep the cursor inside
+    /// the visible window before the next render.
+    fn scroll(&mut self) {
+        let text_rows = self.text_rows();
+        if self.y < self.row_offset {
+            self.row_offset = self.y;
+        }
+        if self.y >= self.row_offset + text_rows {
+            self.row_offset = self.y - text_rows + 1;
+        }
+        if self.x < self.col_offset {
+            self.col_offset = self.x;
+        }
+        // Walk col_offset forward by display columns, not char count, so
+        // wide (2-column) characters can't push the cursor past the right
+        // edge the next render draws.
+        let cursor_col: usize = self.buffer[self.y][self.col_offset..self.x]
+            .iter()
+            .map(|&c| char_width(c))
+            .sum();
+        if cursor_col >= self.screen_cols {
+            let overflow = cursor_col - self.screen_cols + 1;
+            let mut shed = 0;
+            while self.col_offset < self.x && shed < overflow {
+                shed += char_width(self.buffer[self.y][self.col_offset]);
+                self.col_offset += 1;
+            }
+        }
+    }
+
+    /// Read the file at `path` into the buffer, splitting on `\n`, and
+    /// remember the path for subsequent saves. A missing file is treated as
+    /// a new, empty buffer; any other read failure (permission denied, path
+    /// is a directory, non-UTF-8 contents) is propagated instead of being
+    /// silently swallowed into an empty buffer that would overwrite the real
+    /// file on the next save.
+    fn load_file(&mut self, path: String) -> io::Result<()> {
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        self.buffer = contents
+            .split('\n')
+            .map(|line| line.chars().collect())
+            .collect();
+        if self.buffer.is_empty() {
+            self.buffer.push(Vec::new());
+        }
+        self.filename = Some(path);
+        self.dirty = 0;
+        Ok(())
+    }
+
+    /// Join the buffer with `\n` and write it back to `filename`.
+    fn save_file(&mut self) -> io::Result<()> {
+        let Some(path) = self.filename.clone() else {
+            self.message = "No filename to save to".to_string();
+            return Ok(());
+        };
+        let contents: Vec<String> = self
+            .buffer
+            .iter()
+            .map(|line| line.iter().collect())
+            .collect();
+        std::fs::write(&path, contents.join("\n"))?;
+        self.dirty = 0;
+        self.message = format!("\"{}\" written", path);
+        Ok(())
+    }
+
+    /// Find the next occurrence of `query` at or after `(from_y, from_x)`
+    /// (or, going backward, the last one ending at or before it), wrapping
+    /// around the buffer. When
+    /// `skip_current` is set, a forward search starts just past `from_x`
+    /// instead of at it — used to explicitly step off the match the cursor
+    /// is already sitting on (ARROW_UP/ARROW_DOWN) rather than re-finding it
+    /// (keystroke-driven narrowing, where the existing match should stay put
+    /// as long as it still satisfies the longer query).
+    fn find_match(
+        &self,
+        from_y: usize,
+        from_x: usize,
+        query: &[char],
+        forward: bool,
+        skip_current: bool,
+    ) -> Option<(usize, usize)> {
+        if query.is_empty() || self.buffer.is_empty() {
+            return None;
+        }
+        let total = self.buffer.len();
+        let mut y = from_y;
+        let mut bound_x = if forward {
+            if skip_current { from_x + 1 } else { from_x }
+        } else {
+            from_x
+        };
+        for _ in 0..=total {
+            let line = &self.buffer[y];
+            if forward {
+                if bound_x <= line.len() && let Some(pos) = find_subslice(&line[bound_x..], query)
+                {
+                    return Some((y, bound_x + pos));
+                }
+                y = (y + 1) % total;
+                bound_x = 0;
+            } else {
+                let limit = bound_x.min(line.len());
+                if let Some(pos) = rfind_subslice(&line[..limit], query) {
+                    return Some((y, pos));
+                }
+                y = (y + total - 1) % total;
+                bound_x = self.buffer[y].len();
+            }
+        }
+        None
+    }
+
     fn render(&mut self) -> io::Result<()> {
+        self.scroll();
         self.clear_view()?;
-        for (i, line) in self.buffer.clone().iter().enumerate() {
-            self.write_all(
-                format!("\x1B[{};1H{}", i + 1, String::from_utf8_lossy(line)).as_bytes(),
-            )?;
+        let buffer = self.buffer.clone();
+        let text_rows = self.text_rows();
+        let visible = buffer.iter().skip(self.row_offset).take(text_rows);
+        for (screen_row, line) in visible.enumerate() {
+            let buf_y = self.row_offset + screen_row;
+            // Slice by display columns, not char count, so a wide character
+            // near the right edge can't overflow past `screen_cols`.
+            let mut rendered = String::new();
+            let mut col_width = 0;
+            for (i, &c) in line.iter().enumerate().skip(self.col_offset) {
+                let w = char_width(c);
+                if col_width + w > self.screen_cols {
+                    break;
+                }
+                col_width += w;
+                let in_match = self.highlight.is_some_and(|(hy, hx, hlen)| {
+                    buf_y == hy && i >= hx && i < hx + hlen
+                });
+                if in_match {
+                    rendered.push_str("\x1B[7m");
+                    rendered.push(c);
+                    rendered.push_str("\x1B[m");
+                } else {
+                    rendered.push(c);
+                }
+            }
+            self.write_all(format!("\x1B[{};1H{}", screen_row + 1, rendered).as_bytes())?;
         }
-        self.write_all(format!("\x1B[{};{}H", self.y + 1, self.x + 1).as_bytes())
+        let status_row = self.screen_rows.saturating_sub(1);
+        let status_line = self.status_line();
+        self.write_all(
+            format!("\x1B[{};1H\x1B[7m{}\x1B[m", status_row, status_line).as_bytes(),
+        )?;
+        self.write_all(format!("\x1B[{};1H{}", self.screen_rows, self.message).as_bytes())?;
+        let col: usize = self.buffer[self.y][self.col_offset..self.x]
+            .iter()
+            .map(|&c| char_width(c))
+            .sum::<usize>()
+            + 1;
+        self.write_all(
+            format!("\x1B[{};{}H", self.y - self.row_offset + 1, col).as_bytes(),
+        )
     }
     fn jump_to_top(&mut self) -> io::Result<()> {
         self.x = 0;
@@ -134,118 +786,435 @@ impl<'a> Canvas<'a> {
             self.x -= 1;
         }
     }
+
+    fn move_home(&mut self) {
+        self.x = 0;
+    }
+
+    fn move_end(&mut self) {
+        self.x = self.buffer[self.y].len();
+    }
+
+    fn page_up(&mut self) {
+        let step = self.text_rows();
+        self.y = self.y.saturating_sub(step);
+        self.x = self.x.min(self.buffer[self.y].len());
+    }
+
+    fn page_down(&mut self) {
+        let step = self.text_rows();
+        self.y = (self.y + step).min(self.buffer.len() - 1);
+        self.x = self.x.min(self.buffer[self.y].len());
+    }
+
+    fn insert_char(&mut self, ch: char) {
+        if self.x == self.buffer[self.y].len() {
+            self.buffer[self.y].push(ch);
+        } else {
+            self.buffer[self.y].insert(self.x, ch);
+        }
+        self.x += 1;
+        self.dirty += 1;
+    }
+
+    fn newline(&mut self) {
+        // Only REPL-mode lines are history entries; a committed line in an
+        // open file is just a line of the file, not something to recall
+        // into a future REPL session.
+        if self.history_mode() {
+            let committed: Vec<u8> = self.buffer[self.y].iter().collect::<String>().into_bytes();
+            self.history.push(committed);
+        }
+        self.insert_newline_raw();
+    }
+
+    /// Splits the current line at the cursor without touching `history` —
+    /// used for pasted content, where each embedded `\n` is just a line
+    /// break, not a command the user is resubmitting from history.
+    fn insert_newline_raw(&mut self) {
+        self.dirty += 1;
+        if self.y < self.buffer.len() {
+            self.buffer.insert(self.y + 1, Vec::new());
+        } else {
+            // If we're at the last line, just add the new line at the end
+            self.buffer.push(Vec::new());
+        }
+        if self.x == self.buffer[self.y].len() {
+            self.move_down(); // Move to next line
+        } else {
+            let mut left_over = self.buffer[self.y].drain(self.x..).collect::<Vec<char>>();
+            self.move_down(); // Move to next line
+            self.buffer[self.y].append(&mut left_over);
+        }
+    }
+
+    fn backspace(&mut self) {
+        self.dirty += 1;
+        if self.x > 0 {
+            // If the canvas is not at the beginning of the line, just remove the character
+            self.buffer[self.y].remove(self.x - 1);
+            self.x -= 1;
+        } else if self.y > 0 {
+            // Move the canvas to the previous line
+            self.y -= 1;
+            // Remove the current line and get its content
+            let mut left_over: Vec<char> = self.buffer.remove(self.y + 1); // Get the next line
+            let new_x = self.buffer[self.y].len();
+            // Append the current line to the previous one
+            self.buffer[self.y].append(&mut left_over);
+            // Adjust the canvas position: it should remain where it was in the previous line
+            self.x = new_x;
+        }
+        // At the very beginning of the buffer, do nothing
+    }
+
+    fn delete_forward(&mut self) {
+        self.dirty += 1;
+        if self.x < self.buffer[self.y].len() {
+            self.buffer[self.y].remove(self.x);
+        } else if self.y + 1 < self.buffer.len() {
+            let mut next = self.buffer.remove(self.y + 1);
+            self.buffer[self.y].append(&mut next);
+        }
+    }
+
+    fn kill_to_eol(&mut self) {
+        self.dirty += 1;
+        self.buffer[self.y].truncate(self.x);
+    }
+
+    fn kill_to_start(&mut self) {
+        self.dirty += 1;
+        self.buffer[self.y].drain(0..self.x);
+        self.x = 0;
+    }
+
+    /// Whether the cursor sits on the current/last input line, i.e. the one
+    /// history navigation should act on.
+    fn on_last_line(&self) -> bool {
+        self.y == self.buffer.len() - 1
+    }
+
+    /// Whether ARROW_UP/ARROW_DOWN should recall shell-style history rather
+    /// than just move the cursor. History is a REPL-mode concept: once a
+    /// real file is open, its last line is just another buffer line, not an
+    /// in-progress entry to recall into.
+    fn history_mode(&self) -> bool {
+        self.filename.is_none()
+    }
+
+    fn history_prev(&mut self) {
+        let current: Vec<u8> = self.buffer[self.y].iter().collect::<String>().into_bytes();
+        if let Some(line) = self.history.prev(&current) {
+            self.buffer[self.y] = String::from_utf8_lossy(&line).chars().collect();
+            self.x = self.buffer[self.y].len();
+        }
+    }
+
+    fn history_next(&mut self) {
+        if let Some(line) = self.history.next() {
+            self.buffer[self.y] = String::from_utf8_lossy(&line).chars().collect();
+            self.x = self.buffer[self.y].len();
+        }
+    }
+}
+
+/// Incremental search triggered by Ctrl-F: every keystroke narrows or moves
+/// the match, ARROW_UP/ARROW_DOWN step to the previous/next match, ESC
+/// cancels back to the cursor position the search started from, and ENTER
+/// accepts wherever the search landed.
+fn incremental_search(
+    canvas: &mut Canvas,
+    stdin: &mut io::Stdin,
+    stdin_fd: RawFd,
+) -> io::Result<()> {
+    let (orig_x, orig_y, orig_row_offset, orig_col_offset) =
+        (canvas.x, canvas.y, canvas.row_offset, canvas.col_offset);
+    let mut query: Vec<char> = Vec::new();
+    let mut query_decoder = Utf8Decoder::new();
+    let mut last_match: Option<(usize, usize)> = None;
+
+    loop {
+        canvas.message = format!("Search: {}", query.iter().collect::<String>());
+        canvas.render()?;
+
+        let mut buf = [0; 1];
+        if stdin.read(&mut buf)? == 0 {
+            continue;
+        }
+
+        if buf[0] == Key::ESCAPE {
+            // A lone ESC press has no more bytes following it, and raw mode's
+            // VMIN=1/VTIME=0 means a blocking read would otherwise stall
+            // until the *next* keystroke and eat it. Peek with a short
+            // timeout so a bare ESC can cancel immediately instead.
+            if input_ready_within(stdin_fd, 50) {
+                let mut seq = [0; 2];
+                if stdin.read(&mut seq)? == 2 && seq[0] == b'[' {
+                    let from = last_match.unwrap_or((orig_y, orig_x));
+                    let step = match seq[1] {
+                        b'A' => Some(false), // ARROW_UP: previous match
+                        b'B' => Some(true),  // ARROW_DOWN: next match
+                        _ => None,
+                    };
+                    if let Some(forward) = step
+                        && let Some(m) = canvas.find_match(from.0, from.1, &query, forward, true)
+                    {
+                        last_match = Some(m);
+                        canvas.y = m.0;
+                        canvas.x = m.1;
+                        canvas.highlight = Some((m.0, m.1, query.len()));
+                    }
+                    continue;
+                }
+            }
+            // Plain ESC: cancel and restore the pre-search cursor exactly.
+            canvas.x = orig_x;
+            canvas.y = orig_y;
+            canvas.row_offset = orig_row_offset;
+            canvas.col_offset = orig_col_offset;
+            canvas.message.clear();
+            canvas.highlight = None;
+            return Ok(());
+        }
+
+        match buf[0] {
+            Key::ENTER => {
+                canvas.message.clear();
+                canvas.highlight = None;
+                return Ok(());
+            }
+            Key::BACKSPACE => {
+                query.pop();
+            }
+            // Fed through the same incremental decoder as normal typing, so a
+            // multibyte search term doesn't get split into garbage chars.
+            c => {
+                if let Some(ch) = query_decoder.feed(c) {
+                    query.push(ch);
+                }
+            }
+        }
+
+        if query.is_empty() {
+            last_match = None;
+            canvas.highlight = None;
+        } else {
+            // Search from the existing match's own start (not past it), so
+            // an extended query that still fits there doesn't jump ahead to
+            // the next occurrence.
+            let from = last_match.unwrap_or((orig_y, orig_x));
+            if let Some(m) = canvas.find_match(from.0, from.1, &query, true, false) {
+                last_match = Some(m);
+                canvas.y = m.0;
+                canvas.x = m.1;
+                canvas.highlight = Some((m.0, m.1, query.len()));
+            } else {
+                canvas.highlight = None;
+            }
+        }
+    }
 }
 
 fn main() -> io::Result<()> {
     let mut stdin = io::stdin();
     let stdin_fd = stdin.lock().as_raw_fd();
 
-    let mut canvas = Canvas::new();
+    let history_path = history_path();
+    let history = History::load(&history_path, HISTORY_MAX_LEN)?;
+
+    let (screen_rows, screen_cols) = window_size(stdin_fd);
+    let mut canvas = Canvas::new(history, screen_rows, screen_cols);
+    if let Some(path) = std::env::args().nth(1) {
+        canvas.load_file(path)?;
+    }
     canvas.clear_view()?;
+    let mut quit_times = QUIT_TIMES;
 
-    // Set terminal to raw mode
-    set_raw_mode(stdin_fd)?;
+    // Set terminal to raw mode; restored automatically when this guard drops,
+    // including on panic or an early `?` return.
+    let _raw_mode = RawModeGuard::enable(stdin_fd)?;
     canvas.clear_view()?;
+    install_sigwinch_handler();
+    // Ask the terminal to wrap pastes in ESC [ 200 ~ / ESC [ 201 ~ instead of
+    // streaming them in as ordinary keystrokes.
+    canvas.write_all(b"\x1B[?2004h")?;
 
-    let mut input_buffer = [0; 4]; // Buffer to handle escape sequences
-    let mut index = 0;
+    let mut key_decoder = KeyDecoder::new();
 
     loop {
+        if WINCH_RECEIVED.swap(false, std::sync::atomic::Ordering::SeqCst) {
+            let (rows, cols) = window_size(stdin_fd);
+            canvas.screen_rows = rows;
+            canvas.screen_cols = cols;
+            canvas.render()?;
+        }
+
         let mut buf = [0; 1];
-        match stdin.read(&mut buf) {
-            Ok(0) => {
-                // No input available, continue the loop
-            }
-            Ok(_) => {
-                input_buffer[index] = buf[0];
-                index += 1;
-
-                // Handle escape sequences
-                if input_buffer[0] == Key::ESCAPE {
-                    if index >= 3 {
-                        // Handle arrow keys (ESC [ A/B/C/D)
-                        match (input_buffer[1], input_buffer[2]) {
-                            Key::ARROW_UP => {
-                                canvas.move_up(); // Move up
-                            }
-                            Key::ARROW_DOWN => {
-                                canvas.move_down(); // Move down
-                            }
-                            Key::ARROW_RIGHT => {
-                                canvas.move_right(); // Move right
-                            }
-                            Key::ARROW_LEFT => {
-                                canvas.move_left();
-                            }
-                            _ => (),
-                        }
-                        index = 0;
-                    }
+        let cmd = match stdin.read(&mut buf) {
+            Ok(0) => continue, // No input available, continue the loop
+            Ok(_) => match key_decoder.feed(buf[0]) {
+                Some(cmd) => cmd,
+                None => continue, // Mid-sequence; wait for the rest of it
+            },
+            Err(_) => break,
+        };
+
+        if let Cmd::Quit = cmd {
+            if canvas.dirty > 0 && quit_times > 0 {
+                quit_times -= 1;
+                if quit_times == 0 {
+                    break;
+                }
+                canvas.message = format!(
+                    "Unsaved changes! Press Ctrl-D {} more time(s) to quit without saving",
+                    quit_times
+                );
+                canvas.render()?;
+                continue;
+            }
+            break;
+        }
+        quit_times = QUIT_TIMES;
+
+        match cmd {
+            Cmd::Insert(ch) => canvas.insert_char(ch),
+            Cmd::Newline => canvas.newline(),
+            Cmd::Backspace => canvas.backspace(),
+            Cmd::Delete => canvas.delete_forward(),
+            Cmd::MoveUp => {
+                if canvas.history_mode() && canvas.on_last_line() {
+                    canvas.history_prev();
                 } else {
-                    // Handle regular characters
-                    match buf[0] {
-                        Key::CTRL_D => {
-                            break;
-                        }
-                        Key::ENTER => {
-                            if canvas.y < canvas.buffer.len() {
-                                canvas.buffer.insert(canvas.y + 1, Vec::new());
-                            } else {
-                                // If we're at the last line, just add the new line at the end
-                                canvas.buffer.push(Vec::new());
-                            }
-                            if canvas.x == canvas.buffer[canvas.y].len() {
-                                canvas.move_down(); // Move to next line
-                            } else {
-                                let mut left_over = canvas.buffer[canvas.y]
-                                    .drain(canvas.x..)
-                                    .collect::<Vec<u8>>();
-                                canvas.move_down(); // Move to next line
-                                canvas.buffer[canvas.y].append(&mut left_over);
-                            }
-                        }
-                        Key::BACKSPACE => {
-                            if canvas.x > 0 {
-                                // If the canvas is not at the beginning of the line, just remove the character
-                                canvas.buffer[canvas.y].remove(canvas.x - 1);
-                                canvas.x -= 1;
-                            } else if canvas.y > 0 {
-                                // Move the canvas to the previous line
-                                canvas.y -= 1;
-                                // Remove the current line and get its content
-                                let mut left_over: Vec<u8> = canvas.buffer.remove(canvas.y + 1); // Get the next line
-                                let new_x = canvas.buffer[canvas.y].len();
-                                // Append the current line to the previous one
-                                canvas.buffer[canvas.y].append(&mut left_over);
-                                // Adjust the canvas position: it should remain where it was in the previous line
-                                canvas.x = new_x;
-                            } else {
-                                // At the very beginning of the buffer, do nothing
-                            }
-                        }
-                        c => {
-                            if canvas.x == canvas.buffer[canvas.y].len() {
-                                canvas.buffer[canvas.y].push(c);
-                            } else {
-                                canvas.buffer[canvas.y].insert(canvas.x, c);
-                            }
-                            canvas.x += 1;
-                        }
-                    }
-                    index = 0;
+                    canvas.move_up();
                 }
             }
-            Err(_) => {
-                break;
+            Cmd::MoveDown => {
+                if canvas.history_mode() && canvas.on_last_line() {
+                    canvas.history_next();
+                } else {
+                    canvas.move_down();
+                }
             }
+            Cmd::MoveLeft => canvas.move_left(),
+            Cmd::MoveRight => canvas.move_right(),
+            Cmd::Home => canvas.move_home(),
+            Cmd::End => canvas.move_end(),
+            Cmd::PageUp => canvas.page_up(),
+            Cmd::PageDown => canvas.page_down(),
+            Cmd::KillToEol => canvas.kill_to_eol(),
+            Cmd::KillToStart => canvas.kill_to_start(),
+            Cmd::Save => canvas.save_file()?,
+            Cmd::Search => incremental_search(&mut canvas, &mut stdin, stdin_fd)?,
+            Cmd::PasteStart | Cmd::PasteEnd => {}
+            Cmd::PasteChar('\n') => canvas.insert_newline_raw(),
+            Cmd::PasteChar(ch) => canvas.insert_char(ch),
+            Cmd::Quit => unreachable!("handled above"),
         }
         canvas.render()?;
     }
 
-    // Restore terminal settings and show canvas
+    // `_raw_mode` restores the terminal when it drops at the end of this scope.
+    canvas.write_all(b"\x1B[?2004l")?;
     canvas.jump_to_top()?;
-    restore_mode(stdin_fd)?;
     canvas.clear_view()?;
+    canvas.history.save(&history_path)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_decoder_feeds_ascii_byte_at_a_time() {
+        let mut decoder = Utf8Decoder::new();
+        assert_eq!(decoder.feed(b'a'), Some('a'));
+    }
+
+    #[test]
+    fn utf8_decoder_assembles_multibyte_sequences() {
+        let mut decoder = Utf8Decoder::new();
+        let mut decoded = None;
+        for &b in "é".as_bytes() {
+            decoded = decoder.feed(b);
+        }
+        assert_eq!(decoded, Some('é'));
+
+        decoded = None;
+        for &b in "あ".as_bytes() {
+            decoded = decoder.feed(b);
+        }
+        assert_eq!(decoded, Some('あ'));
+    }
+
+    #[test]
+    fn utf8_decoder_drops_invalid_continuation_and_recovers() {
+        let mut decoder = Utf8Decoder::new();
+        assert_eq!(decoder.feed(0xC0), None); // lead byte, awaiting continuation
+        assert_eq!(decoder.feed(b'a'), None); // not a continuation byte; sequence abandoned
+        assert_eq!(decoder.feed(b'b'), Some('b')); // decoder has recovered
+    }
+
+    #[test]
+    fn history_prev_next_round_trips_through_draft() {
+        let mut history = History::new(10);
+        history.push(b"first".to_vec());
+        history.push(b"second".to_vec());
+
+        assert_eq!(history.prev(b"draft"), Some(b"second".to_vec()));
+        assert_eq!(history.prev(b"draft"), Some(b"first".to_vec()));
+        assert_eq!(history.prev(b"draft"), Some(b"first".to_vec())); // clamped at oldest
+
+        assert_eq!(history.next(), Some(b"second".to_vec()));
+        assert_eq!(history.next(), Some(b"draft".to_vec())); // past newest: restores draft
+        assert_eq!(history.next(), None); // no longer navigating
+    }
+
+    #[test]
+    fn history_skips_consecutive_duplicates() {
+        let mut history = History::new(10);
+        history.push(b"same".to_vec());
+        history.push(b"same".to_vec());
+        assert_eq!(history.entries.len(), 1);
+    }
+
+    #[test]
+    fn history_respects_max_len() {
+        let mut history = History::new(2);
+        history.push(b"a".to_vec());
+        history.push(b"b".to_vec());
+        history.push(b"c".to_vec());
+        assert_eq!(
+            history.entries,
+            VecDeque::from([b"b".to_vec(), b"c".to_vec()])
+        );
+    }
+
+    #[test]
+    fn find_subslice_locates_first_occurrence() {
+        let haystack: Vec<char> = "abcabc".chars().collect();
+        let needle: Vec<char> = "bc".chars().collect();
+        assert_eq!(find_subslice(&haystack, &needle), Some(1));
+    }
+
+    #[test]
+    fn rfind_subslice_locates_last_occurrence() {
+        let haystack: Vec<char> = "abcabc".chars().collect();
+        let needle: Vec<char> = "bc".chars().collect();
+        assert_eq!(rfind_subslice(&haystack, &needle), Some(4));
+    }
+
+    #[test]
+    fn find_subslice_rejects_empty_needle() {
+        let haystack: Vec<char> = "abc".chars().collect();
+        assert_eq!(find_subslice(&haystack, &[]), None);
+    }
+
+    #[test]
+    fn char_width_is_one_for_ascii_and_two_for_wide_chars() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('あ'), 2);
+    }
+}